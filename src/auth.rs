@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use sha2::{Digest, Sha256};
+
+use crate::handler::ResponseError;
+
+/// Permission scope required to call a given endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Read,
+    Write,
+}
+
+/// Pluggable authentication/authorization backend.
+///
+/// `AppState` holds this behind an `Arc<dyn ApiAuth>` so the token-map
+/// backend below can be swapped for e.g. HMAC or JWT verification without
+/// touching the handlers.
+pub trait ApiAuth: Send + Sync {
+    fn authorize(&self, headers: &HeaderMap, scope: Scope) -> Result<(), ResponseError>;
+}
+
+/// Default backend: a static map of `token -> scopes` parsed from the
+/// `AUTH` env var, e.g. `token1:read,write;token2:read`.
+///
+/// Tokens are hashed to a fixed-length digest and compared with an
+/// XOR-accumulating loop so neither a mismatched length nor an early byte
+/// difference leaks timing information.
+pub struct TokenAuth {
+    tokens: HashMap<[u8; 32], Vec<Scope>>,
+}
+
+impl TokenAuth {
+    pub fn from_env_var(value: &str) -> Self {
+        let mut tokens = HashMap::new();
+        for entry in value.split(';').filter(|s| !s.is_empty()) {
+            let (token, scopes) = entry
+                .split_once(':')
+                .unwrap_or_else(|| panic!("malformed AUTH entry: {entry}"));
+            let scopes = scopes
+                .split(',')
+                .map(|s| match s {
+                    "read" => Scope::Read,
+                    "write" => Scope::Write,
+                    other => panic!("unknown scope in AUTH entry: {other}"),
+                })
+                .collect();
+            tokens.insert(hash_token(token), scopes);
+        }
+        Self { tokens }
+    }
+}
+
+impl ApiAuth for TokenAuth {
+    fn authorize(&self, headers: &HeaderMap, scope: Scope) -> Result<(), ResponseError> {
+        let header = headers
+            .get("x-auth")
+            .and_then(|v| v.to_str().ok())
+            .ok_or(ResponseError::Forbidden)?;
+        let hashed = hash_token(header);
+
+        let authorized = self
+            .tokens
+            .iter()
+            .any(|(token, scopes)| constant_time_eq(token, &hashed) && scopes.contains(&scope));
+
+        authorized.then_some(()).ok_or(ResponseError::Forbidden)
+    }
+}
+
+fn hash_token(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Compare two fixed-length digests without branching on the first
+/// mismatched byte.
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
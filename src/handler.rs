@@ -1,32 +1,40 @@
-use std::io::{Cursor, SeekFrom};
+use std::io::Cursor;
 use std::sync::Arc;
 use std::time;
 
 use axum::extract::State;
 use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
-use serde::Deserialize;
-use tokio::io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use axum::Json;
+use serde::{Deserialize, Serialize};
 
+use crate::auth::Scope;
+use crate::working_file::{GuestAccount, GuestStore};
 use crate::AppState;
 
 pub enum ResponseError {
     Forbidden,
     Internal(String),
     InvalidJson,
+    RateLimited { retry_after: u64 },
 }
 
 impl IntoResponse for ResponseError {
     fn into_response(self) -> axum::response::Response {
         match self {
-            Self::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+            Self::Forbidden => (StatusCode::FORBIDDEN, "forbidden").into_response(),
             Self::Internal(s) => {
                 eprintln!("internal error: {s}");
-                (StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal server error").into_response()
             }
-            Self::InvalidJson => (StatusCode::BAD_REQUEST, "invalid json"),
+            Self::InvalidJson => (StatusCode::BAD_REQUEST, "invalid json").into_response(),
+            Self::RateLimited { retry_after } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [("retry-after", retry_after.to_string())],
+                "too many requests",
+            )
+                .into_response(),
         }
-        .into_response()
     }
 }
 
@@ -35,156 +43,173 @@ pub async fn count(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
 ) -> Result<String, ResponseError> {
-    verify_auth(&state.auth, &headers)?;
+    state.auth.authorize(&headers, Scope::Read)?;
 
-    let mut file = state
-        .dest_file
-        .lock()
-        .await
-        .open()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    file.seek(SeekFrom::Start(0))
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    let mut lines = BufReader::new(&mut file).lines();
-    let mut count = 0;
-    while (lines
-        .next_line()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?)
-    .is_some()
-    {
-        count += 1;
-    }
+    let count = state.store.lock().await.count();
 
     Ok(count.to_string())
 }
 
+#[derive(Serialize)]
+pub struct AppendSummary {
+    accepted: usize,
+    rejected: Vec<RejectedEntry>,
+    duplicates: usize,
+}
+
+#[derive(Serialize)]
+pub struct RejectedEntry {
+    index: usize,
+    reason: String,
+}
+
 /// Append guest accounts to the guest accounts file
+///
+/// Every entry in the body is validated before anything is written: if any
+/// entry is malformed or already past the configured max age, the whole
+/// batch is rejected with `400` and a summary listing which entries failed
+/// and why, so the scraper can fix up its batch instead of getting a bare
+/// `200` for a partially-applied write.
 pub async fn append(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     body: String,
-) -> Result<(), ResponseError> {
-    verify_auth(&state.auth, &headers)?;
+) -> Result<impl IntoResponse, ResponseError> {
+    state.auth.authorize(&headers, Scope::Write)?;
+
+    let current_time = time::UNIX_EPOCH
+        .elapsed()
+        .map_err(|e| ResponseError::Internal(e.to_string()))?
+        .as_secs() as i64;
+    let max_age_secs = state.max_age_days * 24 * 60 * 60;
 
-    // Read all lines in body and append them to the file
-    let mut file = state
-        .dest_file
-        .lock()
-        .await
-        .open()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    file.seek(SeekFrom::End(0))
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
     let cursor = Cursor::new(body);
     let deserializer = serde_json::Deserializer::from_reader(cursor);
-    let values: Vec<_> = deserializer.into_iter::<serde_json::Value>().collect();
-    for value in values {
-        let value = value.map_err(|_| ResponseError::InvalidJson)?;
-        let line =
-            serde_json::to_string(&value).map_err(|e| ResponseError::Internal(e.to_string()))?;
-        let line = format!("{}\n", line);
-        file.write_all(line.as_bytes())
-            .await
-            .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    }
-    file.flush()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
 
-    Ok(())
-}
-
-/// Remove all guest accounts older than a specified max age
-pub async fn prune(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-) -> Result<(), ResponseError> {
-    verify_auth(&state.auth, &headers)?;
+    let mut rejected = Vec::new();
+    let mut candidates = Vec::new();
+    for (index, value) in deserializer.into_iter::<serde_json::Value>().enumerate() {
+        let value = match value {
+            Ok(value) => value,
+            Err(e) => {
+                rejected.push(RejectedEntry {
+                    index,
+                    reason: format!("invalid json: {e}"),
+                });
+                continue;
+            }
+        };
+        let account: GuestAccount = match serde_json::from_value(value.clone()) {
+            Ok(account) => account,
+            Err(e) => {
+                rejected.push(RejectedEntry {
+                    index,
+                    reason: format!("malformed guest account: {e}"),
+                });
+                continue;
+            }
+        };
+        let id = match account.user.id_str.parse::<u64>() {
+            Ok(id) => id,
+            Err(e) => {
+                rejected.push(RejectedEntry {
+                    index,
+                    reason: format!("invalid id_str: {e}"),
+                });
+                continue;
+            }
+        };
+        if current_time - id_to_ts(id) >= max_age_secs {
+            rejected.push(RejectedEntry {
+                index,
+                reason: "account already expired".to_string(),
+            });
+            continue;
+        }
 
-    #[derive(Deserialize)]
-    struct GuestAccount {
-        user: User,
+        let line =
+            serde_json::to_string(&value).map_err(|e| ResponseError::Internal(e.to_string()))?;
+        candidates.push((id, line));
     }
 
-    #[derive(Deserialize)]
-    struct User {
-        id_str: String,
+    if !rejected.is_empty() {
+        let summary = AppendSummary {
+            accepted: 0,
+            rejected,
+            duplicates: 0,
+        };
+        return Ok((StatusCode::BAD_REQUEST, Json(summary)));
     }
 
-    // Get the current time
-    let current_time = time::UNIX_EPOCH
-        .elapsed()
-        .map_err(|e| ResponseError::Internal(e.to_string()))?
-        .as_secs() as i64;
-    const MAX_AGE_DAYS: i64 = 25;
-    const MAX_AGE_SECS: i64 = MAX_AGE_DAYS * 24 * 60 * 60;
-
-    // Read the guest accounts file
-    let mut file = state
-        .dest_file
-        .lock()
-        .await
-        .open()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    file.seek(SeekFrom::Start(0))
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    let mut lines = BufReader::new(&mut file).lines();
-
-    // Lines to keep
-    let mut preserved_lines = Vec::new();
-
-    // For each line, check its ID, convert it to a timestamp, and check its age.
-    // Keep it if it's less than the specified age
-    while let Some(line) = lines
-        .next_line()
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?
-    {
-        let account: GuestAccount =
-            serde_json::from_str(&line).map_err(|e| ResponseError::Internal(e.to_string()))?;
-        let id = account
-            .user
-            .id_str
-            .parse::<u64>()
-            .map_err(|e| ResponseError::Internal(e.to_string()))?;
-        let ts = id_to_ts(id);
-        if current_time - ts < MAX_AGE_SECS {
-            preserved_lines.push(line);
+    let mut store = state.store.lock().await;
+    let mut accepted = 0;
+    let mut duplicates = 0;
+    for (id, line) in candidates {
+        if store.insert(id, line) {
+            accepted += 1;
+        } else {
+            duplicates += 1;
         }
     }
 
-    // Truncate the file and add all lines that should be preserved
-    file.set_len(0)
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    file.seek(SeekFrom::Start(0))
-        .await
-        .map_err(|e| ResponseError::Internal(e.to_string()))?;
-    for line in preserved_lines {
-        let line = format!("{}\n", line);
-        file.write_all(line.as_bytes())
+    if accepted > 0 {
+        store
+            .flush()
             .await
             .map_err(|e| ResponseError::Internal(e.to_string()))?;
     }
 
-    file.flush()
+    let summary = AppendSummary {
+        accepted,
+        rejected: Vec::new(),
+        duplicates,
+    };
+    Ok((StatusCode::OK, Json(summary)))
+}
+
+#[derive(Deserialize, Default)]
+pub struct PruneRequest {
+    /// Overrides the server's configured `MAX_AGE_DAYS` for this call.
+    max_age_days: Option<i64>,
+}
+
+/// Remove all guest accounts older than a specified max age
+pub async fn prune(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<(), ResponseError> {
+    state.auth.authorize(&headers, Scope::Write)?;
+
+    let request: PruneRequest = if body.trim().is_empty() {
+        PruneRequest::default()
+    } else {
+        serde_json::from_str(&body).map_err(|_| ResponseError::InvalidJson)?
+    };
+    let max_age_days = request.max_age_days.unwrap_or(state.max_age_days);
+    let max_age_secs = max_age_days * 24 * 60 * 60;
+
+    let mut store = state.store.lock().await;
+    prune_store(&mut store, max_age_secs)
         .await
         .map_err(|e| ResponseError::Internal(e.to_string()))?;
 
     Ok(())
 }
 
-fn verify_auth(auth: &String, headers: &HeaderMap) -> Result<(), ResponseError> {
-    (headers.get("x-auth").ok_or(ResponseError::Forbidden)? == auth)
-        .then_some(())
-        .ok_or(ResponseError::Forbidden)
+/// Remove every account older than `max_age_secs` and persist the result.
+/// Shared by the `/prune` handler and the background prune scheduler so
+/// the snowflake-based age filtering lives in one place.
+pub(crate) async fn prune_store(
+    store: &mut GuestStore,
+    max_age_secs: i64,
+) -> std::io::Result<()> {
+    let current_time = time::UNIX_EPOCH
+        .elapsed()
+        .expect("system clock is before UNIX_EPOCH")
+        .as_secs() as i64;
+    store.retain(|id| current_time - id_to_ts(id) < max_age_secs);
+    store.flush().await
 }
 
 fn id_to_ts(id: u64) -> i64 {
@@ -1,39 +1,121 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::routing::{post, get};
 use axum::Router;
+use auth::{ApiAuth, TokenAuth};
+use axum_server::tls_rustls::RustlsConfig;
 use handler::{append, prune, count};
+use rate_limit::RateLimiter;
 use tokio::sync::Mutex;
-use working_file::WorkingFile;
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use working_file::GuestStore;
 
+mod auth;
 mod handler;
+mod rate_limit;
 mod working_file;
 
+/// Default `/prune` age threshold, in days, when neither the `MAX_AGE_DAYS`
+/// env var nor a request's `max_age_days` field overrides it.
+const DEFAULT_MAX_AGE_DAYS: i64 = 25;
+/// Default interval, in seconds, between background prune sweeps.
+const DEFAULT_PRUNE_INTERVAL_SECS: u64 = 60 * 60;
+/// Default per-token/per-IP request budget enforced by the rate limiter.
+const DEFAULT_RATE_LIMIT_PER_MIN: u32 = 60;
+
 pub struct AppState {
-    auth: String,
-    dest_file: Mutex<WorkingFile>,
+    auth: Arc<dyn ApiAuth>,
+    store: Mutex<GuestStore>,
+    max_age_days: i64,
+    rate_limiter: RateLimiter,
 }
 
 #[tokio::main]
 async fn main() {
     // Read config from env vars
     let port = std::env::var("PORT").unwrap().parse().unwrap();
-    let socket = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+    let bind_addr = env_or("BIND_ADDR", IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+    let socket = SocketAddr::new(bind_addr, port);
     let dest_file_path = std::env::var_os("DEST_FILE").unwrap();
+    let store = GuestStore::load(dest_file_path)
+        .await
+        .expect("failed to load guest accounts file");
+    let max_age_days = env_or("MAX_AGE_DAYS", DEFAULT_MAX_AGE_DAYS);
+    let prune_interval_secs = env_or("PRUNE_INTERVAL_SECS", DEFAULT_PRUNE_INTERVAL_SECS);
+    let rate_limit_per_min = env_or("RATE_LIMIT_PER_MIN", DEFAULT_RATE_LIMIT_PER_MIN);
     let state = Arc::new(AppState {
-        auth: std::env::var("AUTH").unwrap(),
-        dest_file: Mutex::new(WorkingFile::new(dest_file_path)),
+        auth: Arc::new(TokenAuth::from_env_var(&std::env::var("AUTH").unwrap())),
+        store: Mutex::new(store),
+        max_age_days,
+        rate_limiter: RateLimiter::new(rate_limit_per_min),
     });
 
+    // Run periodic pruning in the background
+    tokio::spawn(run_prune_scheduler(Arc::clone(&state), prune_interval_secs));
+
     // Start web server
+    //
+    // `RequestDecompressionLayer` transparently inflates gzip-encoded request
+    // bodies (e.g. large `/append` batches) before handlers extract them, and
+    // `CompressionLayer` compresses responses for clients that send
+    // `Accept-Encoding: gzip`.
     let app = Router::new()
         .route("/count", get(count))
         .route("/append", post(append))
         .route("/prune", post(prune))
+        .route_layer(axum::middleware::from_fn_with_state(
+            Arc::clone(&state),
+            rate_limit::enforce,
+        ))
+        .layer(CompressionLayer::new())
+        .layer(RequestDecompressionLayer::new())
         .with_state(state);
-    axum::Server::bind(&socket)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+
+    // Serve over TLS when TLS_CERT/TLS_KEY are set, otherwise plain HTTP
+    match (
+        std::env::var_os("TLS_CERT"),
+        std::env::var_os("TLS_KEY"),
+    ) {
+        (Some(cert), Some(key)) => {
+            let tls_config = RustlsConfig::from_pem_file(cert, key)
+                .await
+                .expect("failed to load TLS_CERT/TLS_KEY");
+            axum_server::bind_rustls(socket, tls_config)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        }
+        _ => {
+            axum::Server::bind(&socket)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        }
+    }
+}
+
+/// Periodically prune the guest store on `interval_secs`, using the
+/// server's configured `max_age_days`.
+async fn run_prune_scheduler(state: Arc<AppState>, interval_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    let max_age_secs = state.max_age_days * 24 * 60 * 60;
+    loop {
+        interval.tick().await;
+        let mut store = state.store.lock().await;
+        if let Err(e) = handler::prune_store(&mut store, max_age_secs).await {
+            eprintln!("scheduled prune failed: {e}");
+        }
+    }
+}
+
+/// Parse an env var, falling back to `default` if it's unset or invalid.
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
 }
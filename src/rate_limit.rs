@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::Mutex;
+
+use crate::handler::ResponseError;
+use crate::AppState;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Per-token (or per-IP, for unauthenticated requests) request budget.
+///
+/// Guards the single `dest_file` mutex from being saturated by a
+/// misbehaving client: each key gets `limit_per_min` requests per fixed
+/// one-minute window, which opens at the key's first request, before being
+/// rejected with `429 Too Many Requests`.
+pub struct RateLimiter {
+    limit_per_min: u32,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit_per_min: u32) -> Self {
+        Self {
+            limit_per_min,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request for `key`, returning the remaining budget and
+    /// seconds until the window resets, or `RateLimited` once the budget
+    /// for the current window is exhausted.
+    async fn check(&self, key: &str) -> Result<(u32, u64), ResponseError> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().await;
+
+        // Evict windows that have already rolled over so the map stays
+        // bounded by the number of keys active in the last minute, rather
+        // than growing forever (e.g. from rotating source IPs).
+        windows.retain(|_, window| now.duration_since(window.0) < WINDOW);
+
+        let window = windows.entry(key.to_string()).or_insert((now, 0));
+        window.1 += 1;
+        let retry_after = WINDOW.saturating_sub(now.duration_since(window.0)).as_secs();
+
+        if window.1 > self.limit_per_min {
+            return Err(ResponseError::RateLimited { retry_after });
+        }
+
+        Ok((self.limit_per_min - window.1, retry_after))
+    }
+}
+
+/// Identify the caller by their `x-auth` token, falling back to their
+/// client IP when the header is absent.
+fn rate_limit_key(headers: &HeaderMap, addr: SocketAddr) -> String {
+    headers
+        .get("x-auth")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| addr.ip().to_string())
+}
+
+/// Axum middleware enforcing `state.rate_limiter`'s per-key budget and
+/// attaching `X-RateLimit-*` headers to every response.
+pub async fn enforce<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let key = rate_limit_key(&headers, addr);
+
+    let (remaining, reset) = match state.rate_limiter.check(&key).await {
+        Ok(budget) => budget,
+        Err(e @ ResponseError::RateLimited { retry_after }) => {
+            let mut response = e.into_response();
+            let headers = response.headers_mut();
+            headers.insert("x-ratelimit-remaining", 0u32.into());
+            headers.insert("x-ratelimit-reset", retry_after.into());
+            return response;
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("x-ratelimit-remaining", remaining.into());
+    headers.insert("x-ratelimit-reset", reset.into());
+    response
+}
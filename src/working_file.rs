@@ -1,25 +1,97 @@
+use std::collections::HashMap;
+use std::io;
 use std::path::{Path, PathBuf};
 
-use tokio::fs::{File, OpenOptions};
+use serde::Deserialize;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
-pub struct WorkingFile {
+/// A single guest account as received from the scraper.
+#[derive(Deserialize)]
+pub struct GuestAccount {
+    pub user: User,
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub id_str: String,
+}
+
+/// In-memory index of guest accounts, keyed by snowflake `id_str` and
+/// backed by an NDJSON file.
+///
+/// The file is read once at startup; `count`/`insert`/`retain` all operate
+/// on the in-memory map so requests never re-scan disk. Mutations are
+/// persisted by writing a temporary file and renaming it over the
+/// destination, so a crash mid-write can't leave a corrupted or truncated
+/// file behind.
+pub struct GuestStore {
     path: PathBuf,
+    guests: HashMap<u64, String>,
 }
 
-impl WorkingFile {
-    pub fn new(path: impl AsRef<Path>) -> Self {
-        Self {
-            path: path.as_ref().to_path_buf(),
+impl GuestStore {
+    /// Load the store from `path`, treating a missing file as empty.
+    pub async fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut guests = HashMap::new();
+        for line in contents.lines() {
+            if let Some(id) = parse_id(line) {
+                guests.insert(id, line.to_string());
+            }
+        }
+
+        Ok(Self { path, guests })
+    }
+
+    /// Number of guest accounts currently in the store.
+    pub fn count(&self) -> usize {
+        self.guests.len()
+    }
+
+    /// Insert `line` (the raw JSON for `id`) unless `id` is already present.
+    /// Returns `true` if the account was newly inserted.
+    pub fn insert(&mut self, id: u64, line: String) -> bool {
+        if self.guests.contains_key(&id) {
+            return false;
         }
+        self.guests.insert(id, line);
+        true
     }
 
-    pub async fn open(&self) -> Result<File, std::io::Error> {
-        OpenOptions::new()
-            .create(true)
-            .read(true)
-            .append(true)
-            .open(&self.path)
-            .await
+    /// Remove every entry for which `keep` returns `false`.
+    pub fn retain(&mut self, mut keep: impl FnMut(u64) -> bool) {
+        self.guests.retain(|&id, _| keep(id));
+    }
+
+    /// Atomically persist the current contents to the destination file.
+    pub async fn flush(&self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone();
+        tmp_path.set_extension("tmp");
+
+        let mut contents = String::with_capacity(self.guests.values().map(String::len).sum());
+        for line in self.guests.values() {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+
+        let mut tmp_file = fs::File::create(&tmp_path).await?;
+        tmp_file.write_all(contents.as_bytes()).await?;
+        tmp_file.flush().await?;
+        drop(tmp_file);
+
+        fs::rename(&tmp_path, &self.path).await
     }
 }
 
+/// Parse a guest account's snowflake ID out of a raw NDJSON line.
+fn parse_id(line: &str) -> Option<u64> {
+    let account: GuestAccount = serde_json::from_str(line).ok()?;
+    account.user.id_str.parse().ok()
+}